@@ -12,10 +12,57 @@ pub struct FileDesc {
     fd: c_int,
 }
 
-pub fn max_len() -> usize {
+// 64-bit macOS/iOS libc rejects any single `read`/`write`/`pread`/`pwrite`
+// whose length is `>= INT_MAX` outright instead of short-reading it, so on
+// those targets we have to clamp well below `ssize_t::MAX`.
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+fn read_limit() -> usize {
+    c_int::max_value() as usize - 1
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "ios")))]
+fn read_limit() -> usize {
     <ssize_t>::max_value() as usize
 }
 
+pub fn max_len() -> usize {
+    read_limit()
+}
+
+// The kernel rejects iovec arrays longer than its own limit with `EINVAL`
+// rather than truncating them, so we have to find that limit ourselves and
+// clamp to it before calling into `readv`/`writev`.
+#[cfg(any(target_os = "freebsd", target_os = "macos", target_os = "netbsd",
+          target_os = "openbsd", target_os = "dragonfly", target_os = "ios"))]
+fn max_iov() -> usize {
+    libc::IOV_MAX as usize
+}
+
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "emscripten"))]
+fn max_iov() -> usize {
+    libc::UIO_MAXIOV as usize
+}
+
+#[cfg(not(any(target_os = "freebsd", target_os = "macos", target_os = "netbsd",
+              target_os = "openbsd", target_os = "dragonfly", target_os = "ios",
+              target_os = "linux", target_os = "android", target_os = "emscripten")))]
+fn max_iov() -> usize {
+    use std::sync::atomic::AtomicUsize;
+
+    static LIMIT: AtomicUsize = AtomicUsize::new(0);
+
+    match LIMIT.load(Ordering::Relaxed) {
+        0 => {
+            let limit = unsafe { libc::sysconf(libc::_SC_IOV_MAX) };
+            // `sysconf` returns -1 on error; fall back to the POSIX minimum.
+            let limit = if limit > 0 { limit as usize } else { 16 };
+            LIMIT.store(limit, Ordering::Relaxed);
+            limit
+        }
+        n => n,
+    }
+}
+
 impl FileDesc {
     pub fn new(fd: c_int) -> FileDesc {
         FileDesc { fd }
@@ -41,11 +88,15 @@ impl FileDesc {
     pub fn read_vectored(&self, bufs: &mut [IoSliceMut<'_>]) -> io::Result<usize> {
         let ret = syscall!(readv(self.fd,
                         bufs.as_ptr() as *const libc::iovec,
-                        cmp::min(bufs.len(), c_int::max_value() as usize) as c_int)
+                        cmp::min(bufs.len(), max_iov()) as c_int)
         )?;
         Ok(ret as usize)
     }
 
+    pub fn is_read_vectored(&self) -> bool {
+        true
+    }
+
     pub fn read_to_end(&self, buf: &mut Vec<u8>) -> io::Result<usize> {
         let mut me = self;
         (&mut me).read_to_end(buf)
@@ -59,6 +110,29 @@ impl FileDesc {
             .map(|n| n as usize)
     }
 
+    // `preadv64`/`pwritev64` are glibc-only symbols; everywhere else
+    // (bionic, musl, emscripten, the BSDs, macOS/iOS) exposes the same
+    // functionality as `preadv`/`pwritev` taking an `off_t` offset instead.
+    #[cfg(target_env = "gnu")]
+    pub fn read_vectored_at(&self, bufs: &mut [IoSliceMut<'_>], offset: u64) -> io::Result<usize> {
+        let ret = syscall!(preadv64(self.fd,
+                        bufs.as_ptr() as *const libc::iovec,
+                        cmp::min(bufs.len(), max_iov()) as c_int,
+                        offset as i64)
+        )?;
+        Ok(ret as usize)
+    }
+
+    #[cfg(not(target_env = "gnu"))]
+    pub fn read_vectored_at(&self, bufs: &mut [IoSliceMut<'_>], offset: u64) -> io::Result<usize> {
+        let ret = syscall!(preadv(self.fd,
+                        bufs.as_ptr() as *const libc::iovec,
+                        cmp::min(bufs.len(), max_iov()) as c_int,
+                        offset as libc::off_t)
+        )?;
+        Ok(ret as usize)
+    }
+
     pub fn write(&self, buf: &[u8]) -> io::Result<usize> {
         let ret = syscall!(write(self.fd,
                         buf.as_ptr() as *const c_void,
@@ -70,7 +144,31 @@ impl FileDesc {
     pub fn write_vectored(&self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
         let ret = syscall!(writev(self.fd,
                          bufs.as_ptr() as *const libc::iovec,
-                         cmp::min(bufs.len(), c_int::max_value() as usize) as c_int)
+                         cmp::min(bufs.len(), max_iov()) as c_int)
+        )?;
+        Ok(ret as usize)
+    }
+
+    pub fn is_write_vectored(&self) -> bool {
+        true
+    }
+
+    #[cfg(target_env = "gnu")]
+    pub fn write_vectored_at(&self, bufs: &[IoSlice<'_>], offset: u64) -> io::Result<usize> {
+        let ret = syscall!(pwritev64(self.fd,
+                         bufs.as_ptr() as *const libc::iovec,
+                         cmp::min(bufs.len(), max_iov()) as c_int,
+                         offset as i64)
+        )?;
+        Ok(ret as usize)
+    }
+
+    #[cfg(not(target_env = "gnu"))]
+    pub fn write_vectored_at(&self, bufs: &[IoSlice<'_>], offset: u64) -> io::Result<usize> {
+        let ret = syscall!(pwritev(self.fd,
+                         bufs.as_ptr() as *const libc::iovec,
+                         cmp::min(bufs.len(), max_iov()) as c_int,
+                         offset as libc::off_t)
         )?;
         Ok(ret as usize)
     }
@@ -88,12 +186,7 @@ impl FileDesc {
     }
 
     pub fn set_cloexec(&self) -> io::Result<()> {
-        let previous = syscall!(fcntl(self.fd, libc::F_GETFD))?;
-        let new = previous | libc::FD_CLOEXEC;
-        if new != previous {
-            syscall!(fcntl(self.fd, libc::F_SETFD, new))?;
-        }
-        Ok(())
+        set_cloexec_raw(self.fd)
     }
 
     pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
@@ -143,6 +236,120 @@ impl FileDesc {
         }
         syscall!(fcntl(fd, libc::F_DUPFD, 0)).and_then(make_filedesc)
     }
+
+    /// Sends `bufs` over this descriptor (expected to wrap an `AF_UNIX`
+    /// socket), passing `fds` alongside it as an `SCM_RIGHTS` control
+    /// message so the peer process gains its own open descriptors for them.
+    pub fn send_with_fds(&self, bufs: &[IoSlice<'_>], fds: &[c_int]) -> io::Result<usize> {
+        let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+        msg.msg_iov = bufs.as_ptr() as *mut libc::iovec;
+        msg.msg_iovlen = bufs.len() as _;
+
+        // No fds to pass: leave msg_control null rather than sending a
+        // zero-payload SCM_RIGHTS control message for nothing.
+        let mut control;
+        if !fds.is_empty() {
+            let control_len = unsafe { libc::CMSG_SPACE((fds.len() * mem::size_of::<c_int>()) as libc::size_t) };
+            control = vec![0u8; control_len as usize];
+            msg.msg_control = control.as_mut_ptr() as *mut c_void;
+            msg.msg_controllen = control_len as _;
+
+            unsafe {
+                let cmsg = libc::CMSG_FIRSTHDR(&msg);
+                (*cmsg).cmsg_level = libc::SOL_SOCKET;
+                (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+                (*cmsg).cmsg_len = libc::CMSG_LEN((fds.len() * mem::size_of::<c_int>()) as libc::size_t) as _;
+                std::ptr::copy_nonoverlapping(
+                    fds.as_ptr(),
+                    libc::CMSG_DATA(cmsg) as *mut c_int,
+                    fds.len(),
+                );
+            }
+        }
+
+        let ret = syscall!(sendmsg(self.fd, &msg, 0))?;
+        Ok(ret as usize)
+    }
+
+    /// Receives data into `bufs` from this descriptor, extracting any
+    /// `SCM_RIGHTS` descriptors the peer passed alongside it into `fds`.
+    /// Any descriptors the kernel delivers beyond `fds.len()` are closed
+    /// rather than leaked.
+    ///
+    /// Returns `(bytes_received, fds_received)`. CLOEXEC is set on every
+    /// descriptor landing in `fds` before this returns, closing the `exec`
+    /// race atomically via `MSG_CMSG_CLOEXEC` on Linux; on other targets
+    /// it's a best-effort `fcntl` applied just after `recvmsg`, so a
+    /// concurrent `exec` on another thread could still observe the
+    /// descriptor briefly without CLOEXEC set.
+    pub fn recv_with_fds(&self, bufs: &mut [IoSliceMut<'_>], fds: &mut [c_int]) -> io::Result<(usize, usize)> {
+        let control_len = unsafe { libc::CMSG_SPACE((fds.len() * mem::size_of::<c_int>()) as libc::size_t) };
+        let mut control = vec![0u8; control_len as usize];
+
+        let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+        msg.msg_iov = bufs.as_ptr() as *mut libc::iovec;
+        msg.msg_iovlen = bufs.len() as _;
+        msg.msg_control = control.as_mut_ptr() as *mut c_void;
+        msg.msg_controllen = control_len as _;
+
+        let flags = cfg_msg_cmsg_cloexec();
+        let ret = syscall!(recvmsg(self.fd, &mut msg, flags))?;
+
+        let mut received = 0;
+        unsafe {
+            let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+            while !cmsg.is_null() {
+                if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                    let data = libc::CMSG_DATA(cmsg) as *const c_int;
+                    let n = ((*cmsg).cmsg_len as usize - libc::CMSG_LEN(0) as usize) / mem::size_of::<c_int>();
+                    // CMSG alignment padding means the payload can carry more
+                    // fds than `fds` has room for. Anything past `fds.len()`
+                    // never gets a slot to live in, so close it here instead
+                    // of leaking an open descriptor.
+                    for i in 0..n {
+                        let fd = *data.add(i);
+                        if received < fds.len() {
+                            fds[received] = fd;
+                            received += 1;
+                        } else {
+                            let _ = syscall!(close(fd));
+                        }
+                    }
+                }
+                cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+            }
+        }
+
+        // `MSG_CMSG_CLOEXEC` isn't available everywhere, so set CLOEXEC
+        // ourselves on whatever we received. Off Linux this only narrows the
+        // race: the descriptors lack CLOEXEC between `recvmsg` and here.
+        for &fd in &fds[..received] {
+            let _ = set_cloexec_raw(fd);
+        }
+
+        Ok((ret as usize, received))
+    }
+}
+
+// Sets CLOEXEC on a raw fd we don't own, used to finish securing descriptors
+// just received via `recv_with_fds` without affecting their lifetime.
+fn set_cloexec_raw(fd: c_int) -> io::Result<()> {
+    let previous = syscall!(fcntl(fd, libc::F_GETFD))?;
+    let new = previous | libc::FD_CLOEXEC;
+    if new != previous {
+        syscall!(fcntl(fd, libc::F_SETFD, new))?;
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn cfg_msg_cmsg_cloexec() -> c_int {
+    libc::MSG_CMSG_CLOEXEC
+}
+
+#[cfg(not(target_os = "linux"))]
+fn cfg_msg_cmsg_cloexec() -> c_int {
+    0
 }
 
 impl<'a> Read for &'a FileDesc {